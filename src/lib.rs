@@ -0,0 +1,1331 @@
+//! The `nihvm` interpreter core, assembler, and disassembler.
+//!
+//! With the default `std` feature disabled, this crate compiles against `core` + `alloc`
+//! only, so the VM can be hosted inside a kernel or other environment without a standard
+//! library. The demo binary in `src/main.rs` needs `std` (for stdin/stdout and the process
+//! entry point) and is built only when the `std` feature is enabled.
+#![cfg_attr(not(feature = "std"), no_std)]
+// This crate is written in an intentionally old (2015-edition-era) style, including `try!`
+// instead of `?` and explicit field names in struct literals; these lints fire on that style
+// rather than on anything incorrect.
+#![allow(deprecated)]
+#![allow(clippy::redundant_field_names)]
+#![allow(clippy::needless_lifetimes)]
+#![allow(clippy::chars_last_cmp)]
+#![allow(clippy::chars_next_cmp)]
+#![allow(clippy::same_item_push)]
+#![allow(clippy::match_like_matches_macro)]
+#![allow(clippy::manual_is_multiple_of)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+use std::boxed::Box;
+#[cfg(feature = "std")]
+use std::string::String;
+#[cfg(feature = "std")]
+use std::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+#[cfg(feature = "std")]
+use std::mem;
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeSet;
+#[cfg(not(feature = "std"))]
+use core::mem;
+
+macro_rules! define_instructions {
+    (variant, value, name, operands, stack_args, stack_effect
+     $($variant:ident,
+       $value:expr,
+       $name:expr,
+       $num_operands:expr,
+       $num_stack_args:expr,
+       $stack_effect:expr)*) => (
+
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        #[repr(u8)]
+        enum Inst { $($variant = $value),* }
+
+        impl Inst {
+            fn from_u8(inst: u8) -> Option<Inst> {
+                match inst {
+                    $($value => Some(Inst::$variant),)*
+                    _ => None
+                }
+            }
+
+            fn from_str(inst: &str) -> Option<Inst> {
+                match inst {
+                    $($name => Some(Inst::$variant),)*
+                    _ => None
+                }
+            }
+
+            fn name(self) -> &'static str { match self { $(Inst::$variant => $name),* } }
+
+            fn num_operands(self)   -> u8 { match self { $(Inst::$variant => $num_operands),* } }
+            fn num_stack_args(self) -> u8 { match self { $(Inst::$variant => $num_stack_args),* } }
+            fn stack_effect(self)   -> i8 { match self { $(Inst::$variant => $stack_effect),* } }
+        }
+    )
+}
+
+// Bytecode instruction opcodes. The values of these opcodes should never change, to remain
+// compatible with existing bytecode programs.
+define_instructions! {
+    variant, value, name,    operands, stack_args, stack_effect
+    Nop,     0,     "nop",   0,        0,           0
+    Print,   1,     "print", 0,        1,          -1
+    Halt,    2,     "halt",  0,        0,           0
+    Push,    3,     "push",  1,        0,           1
+    Dup,     4,     "dup",   0,        1,           1
+    Pop,     5,     "pop",   0,        1,          -1
+    Swap,    6,     "swap",  0,        2,           0
+    Add,     7,     "add",   0,        2,          -1
+    Sub,     8,     "sub",   0,        2,          -1
+    Mul,     9,     "mul",   0,        2,          -1
+    Div,     10,    "div",   0,        2,          -1
+    Mod,     11,    "mod",   0,        2,          -1
+    Eq,      12,    "eq",    0,        2,          -1
+    Lt,      13,    "lt",    0,        2,          -1
+    Lte,     14,    "lte",   0,        2,          -1
+    Gt,      15,    "gt",    0,        2,          -1
+    Gte,     16,    "gte",   0,        2,          -1
+    Jz,      17,    "jz",    1,        1,          -1
+    Jnz,     18,    "jnz",   1,        1,          -1
+    Jump,    19,    "jump",  1,        0,           0
+    Call,    20,    "call",  1,        0,           0
+    Ret,     21,    "ret",   0,        0,           0
+    CPush,   22,    "cpush", 0,        1,          -1
+    CPop,    23,    "cpop",  0,        0,           1
+    CDup,    24,    "cdup",  0,        0,           1
+    Load,    25,    "load",  0,        1,           0
+    Store,   26,    "store", 0,        2,          -2
+    Load8,   27,    "load8", 0,        1,           0
+    Store8,  28,    "store8",0,        2,          -2
+    Int,     29,    "int",   1,        0,           0
+    Rdcycle, 30,    "rdcycle", 0,      0,           1
+    Divmod,  31,    "divmod", 0,       2,           0
+}
+
+/// Size in bytes of each page of linear memory. Every load/store is checked against the
+/// boundaries of the page(s) it touches so that out-of-bounds or unmapped accesses fault
+/// instead of reading/writing past the end of the `Vm`'s memory.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Reads a little-endian `i32` out of `bytes[0..4]`, panicking if it's shorter than that.
+/// Used both by the bytecode cursor below and by `Vm`'s memory load/store helpers.
+fn read_i32_le(bytes: &[u8]) -> i32 {
+    let value = bytes[0] as u32
+        | (bytes[1] as u32) << 8
+        | (bytes[2] as u32) << 16
+        | (bytes[3] as u32) << 24;
+    value as i32
+}
+
+/// Writes `val` into `bytes[0..4]` as a little-endian `i32`, panicking if it's shorter than
+/// that.
+fn write_i32_le(bytes: &mut [u8], val: i32) {
+    let val = val as u32;
+    bytes[0] = val as u8;
+    bytes[1] = (val >> 8) as u8;
+    bytes[2] = (val >> 16) as u8;
+    bytes[3] = (val >> 24) as u8;
+}
+
+/// A minimal cursor over a borrowed byte slice, tracking a read position and decoding
+/// little-endian `i32` operands by hand. Replaces `std::io::Cursor` + `byteorder` so the
+/// bytecode decoder doesn't depend on `std`.
+struct ByteCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    fn new(data: &'a [u8]) -> ByteCursor<'a> {
+        ByteCursor { data: data, pos: 0 }
+    }
+
+    fn position(&self) -> u64 {
+        self.pos as u64
+    }
+
+    fn set_position(&mut self, pos: u64) {
+        self.pos = pos as usize;
+    }
+
+    fn read_u8(&mut self) -> Result<u8, VmError> {
+        let byte = try!(self.data.get(self.pos).cloned().ok_or(VmError::UnexpectedProgramEnd));
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn read_i32(&mut self) -> Result<i32, VmError> {
+        let end = self.pos + 4;
+        let bytes = try!(self.data.get(self.pos..end).ok_or(VmError::UnexpectedProgramEnd));
+        let val = read_i32_le(bytes);
+        self.pos = end;
+        Ok(val)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VmError {
+    InvalidOpcode,
+    UnexpectedProgramEnd, // Hit end of program while reading operand.
+    StackOverflow,
+    StackUnderflow,
+    ControlStackOverflow,
+    ControlStackUnderflow,
+    MemoryFault { addr: u32 },
+    UnhandledTrap(i32),
+    TimerExpired,
+    DivideByZero,
+    ArithmeticOverflow,
+}
+
+/// The reason a trap was raised, passed to the installed `TrapHandler` (if any) so it can
+/// decide how to recover.
+#[derive(Clone, Copy, Debug)]
+pub enum TrapCause {
+    InvalidOpcode,
+    StackOverflow,
+    StackUnderflow,
+    ControlStackOverflow,
+    ControlStackUnderflow,
+    MemoryFault { addr: u32 },
+    /// Raised by the `int` opcode with a host-defined cause code.
+    Software(i32),
+    /// Raised every `Vm::timer_period` instructions, when a period is configured.
+    Timer,
+    /// Raised by `div`, `mod`, and `divmod` on a zero divisor.
+    DivideByZero,
+    /// Raised by `add`/`sub`/`mul` (when `Vm::checked_arithmetic` is set) or by `div`/`mod`/
+    /// `divmod` on signed overflow (only possible for `i32::MIN / -1`).
+    ArithmeticOverflow,
+}
+
+impl TrapCause {
+    /// Index into `Vm::traps` of the handler slot for this cause.
+    fn trap_index(self) -> usize {
+        match self {
+            TrapCause::InvalidOpcode => 0,
+            TrapCause::StackOverflow => 1,
+            TrapCause::StackUnderflow => 2,
+            TrapCause::ControlStackOverflow => 3,
+            TrapCause::ControlStackUnderflow => 4,
+            TrapCause::MemoryFault { .. } => 5,
+            TrapCause::Software(_) => 6,
+            TrapCause::Timer => 7,
+            TrapCause::DivideByZero => 8,
+            TrapCause::ArithmeticOverflow => 9,
+        }
+    }
+
+    /// The `VmError` this cause becomes when no handler is installed.
+    fn to_vm_error(self) -> VmError {
+        match self {
+            TrapCause::InvalidOpcode => VmError::InvalidOpcode,
+            TrapCause::StackOverflow => VmError::StackOverflow,
+            TrapCause::StackUnderflow => VmError::StackUnderflow,
+            TrapCause::ControlStackOverflow => VmError::ControlStackOverflow,
+            TrapCause::ControlStackUnderflow => VmError::ControlStackUnderflow,
+            TrapCause::MemoryFault { addr } => VmError::MemoryFault { addr: addr },
+            TrapCause::Software(cause) => VmError::UnhandledTrap(cause),
+            TrapCause::Timer => VmError::TimerExpired,
+            TrapCause::DivideByZero => VmError::DivideByZero,
+            TrapCause::ArithmeticOverflow => VmError::ArithmeticOverflow,
+        }
+    }
+}
+
+/// What the interpreter should do after a trap handler has run.
+pub enum TrapAction {
+    /// Skip the faulting instruction and continue with the one after it. For
+    /// `TrapCause::Timer`, which fires before an instruction runs, that instruction is run
+    /// instead of skipped.
+    Resume,
+    /// Continue execution at this bytecode offset, as if it had been jumped to.
+    Jump(u64),
+    /// Stop execution and return this error from `execute`.
+    Abort(VmError),
+}
+
+/// A host-installed routine that gets a chance to recover from a fault instead of aborting
+/// the whole `execute` call.
+pub type TrapHandler = Box<dyn FnMut(&mut Vm, TrapCause) -> TrapAction>;
+
+/// Number of distinct trap slots in `Vm::traps`, one per `TrapCause` variant.
+const NUM_TRAPS: usize = 10;
+
+/// Where `Inst::Print` sends the values it prints. Pluggable so the interpreter core has no
+/// hard dependency on stdout (there is none in `core`/`alloc`).
+pub type OutputSink = Box<dyn FnMut(&mut Vm, i32)>;
+
+/// The default `OutputSink` used by the demo binary in `main`, printing to stdout.
+#[cfg(feature = "std")]
+pub fn stdout_sink(_vm: &mut Vm, val: i32) {
+    println!("{}", val);
+}
+
+pub struct Vm {
+    stack: Box<[i32]>,
+    stack_idx: usize,
+    control_stack: Box<[i32]>,
+    control_stack_idx: usize,
+    memory: Box<[u8]>,
+    mapped_pages: Box<[bool]>,
+    traps: [Option<TrapHandler>; NUM_TRAPS],
+    output: OutputSink,
+    /// Instructions dispatched so far, wrapping on overflow; an instruction the timer
+    /// interrupts is counted again when it is re-executed. Readable from bytecode via
+    /// `rdcycle`.
+    cycle_count: u32,
+    /// When set, a `TrapCause::Timer` fires every time `cycle_count` is a multiple of this.
+    timer_period: Option<u32>,
+    /// When set, `add`/`sub`/`mul` raise `TrapCause::ArithmeticOverflow` on signed overflow
+    /// instead of wrapping.
+    checked_arithmetic: bool,
+}
+
+impl Vm {
+    /// Builds a `Vm` with `stack_size`-deep data and control stacks and `memory_pages` pages
+    /// of linear memory, all mapped. No traps are installed, the timer is disabled,
+    /// arithmetic wraps, and `print` output is discarded until `install_trap`/
+    /// `set_timer_period`/`set_checked_arithmetic`/`set_output` say otherwise.
+    pub fn new(stack_size: usize, memory_pages: usize) -> Vm {
+        Vm {
+            stack: vec![0; stack_size].into_boxed_slice(),
+            stack_idx: 0,
+            control_stack: vec![0; stack_size].into_boxed_slice(),
+            control_stack_idx: 0,
+            memory: vec![0; memory_pages * PAGE_SIZE].into_boxed_slice(),
+            mapped_pages: vec![true; memory_pages].into_boxed_slice(),
+            traps: [None, None, None, None, None, None, None, None, None, None],
+            output: Box::new(|_, _| {}),
+            cycle_count: 0,
+            timer_period: None,
+            checked_arithmetic: false,
+        }
+    }
+
+    /// Redirects `Inst::Print`'s output to `output`.
+    pub fn set_output(&mut self, output: OutputSink) {
+        self.output = output;
+    }
+
+    /// Installs `handler` to run whenever a cause of the same variant as `cause` is raised,
+    /// replacing whatever was previously installed for that variant. The variant's payload
+    /// (the `Software` code, the `MemoryFault` address) does not pick the slot; the handler
+    /// receives the actual cause. Without an installed handler, a raised cause aborts
+    /// `execute` with `cause.to_vm_error()`.
+    pub fn install_trap<F>(&mut self, cause: TrapCause, handler: F)
+        where F: FnMut(&mut Vm, TrapCause) -> TrapAction + 'static
+    {
+        self.traps[cause.trap_index()] = Some(Box::new(handler));
+    }
+
+    /// Sets (or clears) the instruction budget that fires `TrapCause::Timer`.
+    pub fn set_timer_period(&mut self, period: Option<u32>) {
+        self.timer_period = period;
+    }
+
+    /// When `checked` is set, `add`/`sub`/`mul` raise `TrapCause::ArithmeticOverflow` on signed
+    /// overflow instead of wrapping.
+    pub fn set_checked_arithmetic(&mut self, checked: bool) {
+        self.checked_arithmetic = checked;
+    }
+
+    /// Runs `Inst::Print`'s configured `output` sink on `val`, temporarily taking it out of
+    /// `self` so the sink may itself borrow the `Vm` mutably.
+    fn emit(&mut self, val: i32) {
+        let mut output = mem::replace(&mut self.output, Box::new(|_, _| {}));
+        output(self, val);
+        self.output = output;
+    }
+
+    /// Looks up the handler installed for `cause` and runs it, translating the `TrapAction`
+    /// it returns into either continued execution (adjusting `opcodes`'s position) or an
+    /// aborting `Err`. Returns `Err(cause.to_vm_error())` when no handler is installed.
+    fn handle_trap(&mut self,
+                    cause: TrapCause,
+                    opcodes: &mut ByteCursor,
+                    inst_start: u64,
+                    resume_skip: u64)
+                    -> Result<(), VmError> {
+        let index = cause.trap_index();
+        match self.traps[index].take() {
+            Some(mut handler) => {
+                let action = handler(self, cause);
+                self.traps[index] = Some(handler);
+                match action {
+                    TrapAction::Resume => {
+                        opcodes.set_position(inst_start + resume_skip);
+                        Ok(())
+                    }
+                    TrapAction::Jump(addr) => {
+                        opcodes.set_position(addr);
+                        Ok(())
+                    }
+                    TrapAction::Abort(err) => Err(err),
+                }
+            }
+            None => Err(cause.to_vm_error()),
+        }
+    }
+
+    /// Checks that a `width`-byte access at `addr` lands entirely within a single mapped page
+    /// of `memory`, returning `VmError::MemoryFault` otherwise.
+    fn check_memory_access(&self, addr: u32, width: usize) -> Result<(), VmError> {
+        use VmError::*;
+
+        let addr = addr as usize;
+        let end = match addr.checked_add(width) {
+            Some(end) => end,
+            None => return Err(MemoryFault { addr: addr as u32 }),
+        };
+
+        if end > self.memory.len() {
+            return Err(MemoryFault { addr: addr as u32 });
+        }
+
+        let page = addr / PAGE_SIZE;
+        let last_page = (end - 1) / PAGE_SIZE;
+        if page != last_page || !self.mapped_pages.get(page).cloned().unwrap_or(false) {
+            return Err(MemoryFault { addr: addr as u32 });
+        }
+
+        Ok(())
+    }
+
+    fn load32(&self, addr: u32) -> Result<i32, VmError> {
+        try!(self.check_memory_access(addr, 4));
+        let bytes = &self.memory[addr as usize..addr as usize + 4];
+        Ok(read_i32_le(bytes))
+    }
+
+    fn store32(&mut self, addr: u32, val: i32) -> Result<(), VmError> {
+        try!(self.check_memory_access(addr, 4));
+        let bytes = &mut self.memory[addr as usize..addr as usize + 4];
+        write_i32_le(bytes, val);
+        Ok(())
+    }
+
+    fn load8(&self, addr: u32) -> Result<i32, VmError> {
+        try!(self.check_memory_access(addr, 1));
+        Ok(self.memory[addr as usize] as i32)
+    }
+
+    fn store8(&mut self, addr: u32, val: i32) -> Result<(), VmError> {
+        try!(self.check_memory_access(addr, 1));
+        self.memory[addr as usize] = val as u8;
+        Ok(())
+    }
+
+    pub fn execute(&mut self, program: &[u8]) -> Result<(), VmError> {
+        #[inline(always)]
+        fn jump(opcodes: &mut ByteCursor, condition: bool) -> Result<(), VmError> {
+            let delta = try!(opcodes.read_i32());
+            if condition {
+                let operand_size = mem::size_of::<i32>() as i64;
+                let addr = (opcodes.position() as i64 + delta as i64 - operand_size) as u64;
+                opcodes.set_position(addr);
+            }
+            Ok(())
+        }
+
+        use VmError::*;
+
+        let mut opcodes = ByteCursor::new(program);
+
+        loop {
+            let inst_start = opcodes.position();
+            let opcode = match opcodes.read_u8() {
+                Ok(opcode) => opcode,
+                Err(_) => break,
+            };
+
+            let inst = match Inst::from_u8(opcode) {
+                Some(inst) => inst,
+                None => {
+                    try!(self.handle_trap(TrapCause::InvalidOpcode, &mut opcodes, inst_start, 1));
+                    continue;
+                }
+            };
+            let resume_skip = 1 + inst.num_operands() as u64 * 4;
+
+            self.cycle_count = self.cycle_count.wrapping_add(1);
+            if let Some(period) = self.timer_period {
+                if period != 0 && self.cycle_count % period == 0 {
+                    // Unlike a fault, the timer doesn't indict the instruction at `inst_start`,
+                    // so `Resume` must re-execute it rather than skip over it.
+                    try!(self.handle_trap(TrapCause::Timer, &mut opcodes, inst_start, 0));
+                    continue;
+                }
+            }
+
+            if self.stack_idx < inst.num_stack_args() as usize {
+                try!(self.handle_trap(TrapCause::StackUnderflow, &mut opcodes, inst_start, resume_skip));
+                continue;
+            }
+            if self.stack_idx as isize >= self.stack.len() as isize - inst.stack_effect() as isize {
+                try!(self.handle_trap(TrapCause::StackOverflow, &mut opcodes, inst_start, resume_skip));
+                continue;
+            }
+
+            match inst {
+                Inst::Nop => {}
+
+                Inst::Print => {
+                    let val = unsafe { *self.stack.get_unchecked(self.stack_idx - 1) };
+                    self.emit(val);
+                }
+
+                Inst::Halt => {
+                    break;
+                }
+
+                Inst::Push => {
+                    let val = try!(opcodes.read_i32());
+                    let stack_top = try!(self.stack.get_mut(self.stack_idx).ok_or(StackOverflow));
+                    *stack_top = val;
+                }
+
+                Inst::Dup => {
+                    unsafe {
+                        *self.stack.get_unchecked_mut(self.stack_idx) =
+                            *self.stack.get_unchecked(self.stack_idx - 1);
+                    }
+                }
+
+                Inst::Pop => {}
+
+                Inst::Swap => {
+                    unsafe {
+                        let tmp = *self.stack.get_unchecked(self.stack_idx - 1);
+                        *self.stack.get_unchecked_mut(self.stack_idx - 1) =
+                            *self.stack.get_unchecked(self.stack_idx - 2);
+                        *self.stack.get_unchecked_mut(self.stack_idx - 2) = tmp;
+                    }
+                }
+
+                Inst::Add => {
+                    let (lhs, rhs) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    let result = if self.checked_arithmetic {
+                        match lhs.checked_add(rhs) {
+                            Some(result) => result,
+                            None => {
+                                try!(self.handle_trap(TrapCause::ArithmeticOverflow, &mut opcodes,
+                                                       inst_start, resume_skip));
+                                continue;
+                            }
+                        }
+                    } else {
+                        lhs.wrapping_add(rhs)
+                    };
+                    unsafe { *self.stack.get_unchecked_mut(self.stack_idx - 2) = result; }
+                }
+
+                Inst::Sub => {
+                    let (lhs, rhs) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    let result = if self.checked_arithmetic {
+                        match lhs.checked_sub(rhs) {
+                            Some(result) => result,
+                            None => {
+                                try!(self.handle_trap(TrapCause::ArithmeticOverflow, &mut opcodes,
+                                                       inst_start, resume_skip));
+                                continue;
+                            }
+                        }
+                    } else {
+                        lhs.wrapping_sub(rhs)
+                    };
+                    unsafe { *self.stack.get_unchecked_mut(self.stack_idx - 2) = result; }
+                }
+
+                Inst::Mul => {
+                    let (lhs, rhs) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    let result = if self.checked_arithmetic {
+                        match lhs.checked_mul(rhs) {
+                            Some(result) => result,
+                            None => {
+                                try!(self.handle_trap(TrapCause::ArithmeticOverflow, &mut opcodes,
+                                                       inst_start, resume_skip));
+                                continue;
+                            }
+                        }
+                    } else {
+                        lhs.wrapping_mul(rhs)
+                    };
+                    unsafe { *self.stack.get_unchecked_mut(self.stack_idx - 2) = result; }
+                }
+
+                Inst::Div => {
+                    let (lhs, rhs) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    let result = match lhs.checked_div(rhs) {
+                        Some(result) => result,
+                        None => {
+                            let cause = if rhs == 0 {
+                                TrapCause::DivideByZero
+                            } else {
+                                TrapCause::ArithmeticOverflow
+                            };
+                            try!(self.handle_trap(cause, &mut opcodes, inst_start, resume_skip));
+                            continue;
+                        }
+                    };
+                    unsafe { *self.stack.get_unchecked_mut(self.stack_idx - 2) = result; }
+                }
+
+                Inst::Mod => {
+                    let (lhs, rhs) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    let result = match lhs.checked_rem(rhs) {
+                        Some(result) => result,
+                        None => {
+                            let cause = if rhs == 0 {
+                                TrapCause::DivideByZero
+                            } else {
+                                TrapCause::ArithmeticOverflow
+                            };
+                            try!(self.handle_trap(cause, &mut opcodes, inst_start, resume_skip));
+                            continue;
+                        }
+                    };
+                    unsafe { *self.stack.get_unchecked_mut(self.stack_idx - 2) = result; }
+                }
+
+                Inst::Eq => {
+                    unsafe {
+                        let val1 = *self.stack.get_unchecked_mut(self.stack_idx - 1);
+                        let ptr2 = self.stack.get_unchecked_mut(self.stack_idx - 2);
+                        *ptr2 = (*ptr2 == val1) as i32;
+                    }
+                }
+
+                Inst::Lt => {
+                    unsafe {
+                        let val1 = *self.stack.get_unchecked_mut(self.stack_idx - 1);
+                        let ptr2 = self.stack.get_unchecked_mut(self.stack_idx - 2);
+                        *ptr2 = (*ptr2 < val1) as i32;
+                    }
+                }
+
+                Inst::Lte => {
+                    unsafe {
+                        let val1 = *self.stack.get_unchecked_mut(self.stack_idx - 1);
+                        let ptr2 = self.stack.get_unchecked_mut(self.stack_idx - 2);
+                        *ptr2 = (*ptr2 <= val1) as i32;
+                    }
+                }
+
+                Inst::Gt => {
+                    unsafe {
+                        let val1 = *self.stack.get_unchecked_mut(self.stack_idx - 1);
+                        let ptr2 = self.stack.get_unchecked_mut(self.stack_idx - 2);
+                        *ptr2 = (*ptr2 > val1) as i32;
+                    }
+                }
+
+                Inst::Gte => {
+                    unsafe {
+                        let val1 = *self.stack.get_unchecked_mut(self.stack_idx - 1);
+                        let ptr2 = self.stack.get_unchecked_mut(self.stack_idx - 2);
+                        *ptr2 = (*ptr2 >= val1) as i32;
+                    }
+                }
+
+                Inst::Jz => {
+                    let condition = unsafe { *self.stack.get_unchecked(self.stack_idx - 1) };
+                    try!(jump(&mut opcodes, condition == 0));
+                }
+
+                Inst::Jnz => {
+                    let condition = unsafe { *self.stack.get_unchecked(self.stack_idx - 1) };
+                    try!(jump(&mut opcodes, condition != 0));
+                }
+
+                Inst::Jump => {
+                    try!(jump(&mut opcodes, true));
+                }
+
+                Inst::Call => {
+                    if self.control_stack_idx >= self.control_stack.len() {
+                        try!(self.handle_trap(TrapCause::ControlStackOverflow, &mut opcodes, inst_start,
+                                               resume_skip));
+                        continue;
+                    }
+                    unsafe {
+                        *self.control_stack.get_unchecked_mut(self.control_stack_idx) =
+                            opcodes.position() as i32 + 4;
+                    }
+                    try!(jump(&mut opcodes, true));
+                    self.control_stack_idx += 1;
+                }
+
+                Inst::Ret => {
+                    if self.control_stack_idx < 1 {
+                        try!(self.handle_trap(TrapCause::ControlStackUnderflow, &mut opcodes, inst_start,
+                                               resume_skip));
+                        continue;
+                    }
+                    let addr = unsafe {
+                        *self.control_stack.get_unchecked(self.control_stack_idx - 1)
+                    };
+                    opcodes.set_position(addr as u64);
+                    self.control_stack_idx -= 1;
+                }
+
+                Inst::CPush => {
+                    if self.control_stack_idx >= self.control_stack.len() {
+                        try!(self.handle_trap(TrapCause::ControlStackOverflow, &mut opcodes, inst_start,
+                                               resume_skip));
+                        continue;
+                    }
+                    unsafe {
+                        *self.control_stack.get_unchecked_mut(self.control_stack_idx) =
+                            *self.stack.get_unchecked(self.stack_idx - 1);
+                    }
+                    self.control_stack_idx += 1;
+                }
+
+                Inst::CPop => {
+                    if self.control_stack_idx < 1 {
+                        try!(self.handle_trap(TrapCause::ControlStackUnderflow, &mut opcodes, inst_start,
+                                               resume_skip));
+                        continue;
+                    }
+                    unsafe {
+                        *self.stack.get_unchecked_mut(self.stack_idx) =
+                            *self.control_stack.get_unchecked(self.control_stack_idx - 1);
+                    }
+                    self.control_stack_idx -= 1;
+                }
+
+                Inst::CDup => {
+                    if self.control_stack_idx < 1 {
+                        try!(self.handle_trap(TrapCause::ControlStackUnderflow, &mut opcodes, inst_start,
+                                               resume_skip));
+                        continue;
+                    }
+                    unsafe {
+                        *self.stack.get_unchecked_mut(self.stack_idx) =
+                            *self.control_stack.get_unchecked(self.control_stack_idx - 1);
+                    }
+                }
+
+                Inst::Load => {
+                    let addr = unsafe { *self.stack.get_unchecked(self.stack_idx - 1) };
+                    let val = match self.load32(addr as u32) {
+                        Ok(val) => val,
+                        Err(MemoryFault { addr }) => {
+                            try!(self.handle_trap(TrapCause::MemoryFault { addr: addr },
+                                                   &mut opcodes, inst_start, resume_skip));
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    unsafe { *self.stack.get_unchecked_mut(self.stack_idx - 1) = val; }
+                }
+
+                Inst::Store => {
+                    let (addr, val) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    if let Err(MemoryFault { addr }) = self.store32(addr as u32, val) {
+                        try!(self.handle_trap(TrapCause::MemoryFault { addr: addr }, &mut opcodes,
+                                               inst_start, resume_skip));
+                        continue;
+                    }
+                }
+
+                Inst::Load8 => {
+                    let addr = unsafe { *self.stack.get_unchecked(self.stack_idx - 1) };
+                    let val = match self.load8(addr as u32) {
+                        Ok(val) => val,
+                        Err(MemoryFault { addr }) => {
+                            try!(self.handle_trap(TrapCause::MemoryFault { addr: addr },
+                                                   &mut opcodes, inst_start, resume_skip));
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    unsafe { *self.stack.get_unchecked_mut(self.stack_idx - 1) = val; }
+                }
+
+                Inst::Store8 => {
+                    let (addr, val) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    if let Err(MemoryFault { addr }) = self.store8(addr as u32, val) {
+                        try!(self.handle_trap(TrapCause::MemoryFault { addr: addr }, &mut opcodes,
+                                               inst_start, resume_skip));
+                        continue;
+                    }
+                }
+
+                Inst::Int => {
+                    let cause_code = try!(opcodes.read_i32());
+                    try!(self.handle_trap(TrapCause::Software(cause_code), &mut opcodes,
+                                          inst_start, resume_skip));
+                }
+
+                Inst::Rdcycle => {
+                    unsafe {
+                        *self.stack.get_unchecked_mut(self.stack_idx) = self.cycle_count as i32;
+                    }
+                }
+
+                Inst::Divmod => {
+                    let (lhs, rhs) = unsafe {
+                        (*self.stack.get_unchecked(self.stack_idx - 2),
+                         *self.stack.get_unchecked(self.stack_idx - 1))
+                    };
+                    let (quot, rem) = match (lhs.checked_div(rhs), lhs.checked_rem(rhs)) {
+                        (Some(quot), Some(rem)) => (quot, rem),
+                        _ => {
+                            let cause = if rhs == 0 {
+                                TrapCause::DivideByZero
+                            } else {
+                                TrapCause::ArithmeticOverflow
+                            };
+                            try!(self.handle_trap(cause, &mut opcodes, inst_start, resume_skip));
+                            continue;
+                        }
+                    };
+                    unsafe {
+                        *self.stack.get_unchecked_mut(self.stack_idx - 2) = quot;
+                        *self.stack.get_unchecked_mut(self.stack_idx - 1) = rem;
+                    }
+                }
+            }
+
+            self.stack_idx = (self.stack_idx as isize + inst.stack_effect() as isize) as usize;
+        }
+
+        Ok(())
+    }
+}
+
+/// A single assembler diagnostic: the half-open byte range in the source it points at (for
+/// caret-underlined rendering), the 1-indexed line/column of its start, and a message.
+#[derive(Clone, Debug)]
+pub struct AsmError {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Returns the 1-indexed (line, column) of byte offset `offset` in `source`.
+fn locate(source: &str, offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut column = 1;
+    for (i, ch) in source.char_indices() {
+        if i >= offset { break; }
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+fn asm_error(source: &str, start: usize, end: usize, message: String) -> AsmError {
+    let (line, column) = locate(source, start);
+    AsmError { start: start, end: end, line: line, column: column, message: message }
+}
+
+/// Splits `segment` (a slice of `source` starting at byte offset `segment_start`) into
+/// whitespace-separated tokens, keeping each token's byte range in `source`.
+fn tokenize<'a>(segment: &'a str, segment_start: usize) -> Vec<(usize, usize, &'a str)> {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, ch) in segment.char_indices() {
+        if ch.is_whitespace() {
+            if let Some(s) = start.take() {
+                tokens.push((segment_start + s, segment_start + i, &segment[s..i]));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((segment_start + s, segment_start + segment.len(), &segment[s..]));
+    }
+    tokens
+}
+
+/// Assembles `source` into bytecode, or collects every diagnostic found along the way
+/// (unknown instructions, missing/malformed operands, undefined or redefined labels)
+/// instead of aborting at the first one.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Vec<AsmError>> {
+    let mut program: Vec<u8> = Vec::new();
+    let mut errors: Vec<AsmError> = Vec::new();
+
+    // Maps a label name to where it was defined: its byte offset in `source` (so a
+    // redefinition error can point back at it) and its byte offset in `program`.
+    let mut label_definitions: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut label_uses: Vec<(&str, usize, usize, usize)> = Vec::new();
+
+    // Logical lines are separated by '\n' or ';', matching `assemble`'s historical meaning
+    // of "line" (several instructions may share a physical source line via ';').
+    let mut segment_start = 0;
+    let bytes = source.as_bytes();
+    for i in 0..bytes.len() + 1 {
+        if i < bytes.len() && bytes[i] != b'\n' && bytes[i] != b';' { continue; }
+
+        let segment = &source[segment_start..i];
+        let mut tokens = tokenize(segment, segment_start).into_iter();
+        let mut first_token = tokens.next();
+        segment_start = i + 1;
+
+        // Parse an optional label at the start of the line.
+        if let Some((label_start, label_end, label)) = first_token {
+            if label.chars().next_back() == Some(':') {
+                let label_name = &label[..label.len() - 1];
+                match label_definitions.get(label_name).cloned() {
+                    Some((prev_start, _)) => {
+                        let (prev_line, prev_column) = locate(source, prev_start);
+                        errors.push(asm_error(source, label_start, label_end,
+                            format!("Attempted to redefine label '{}' (previously defined at \
+                                     line {}, column {})",
+                                    label_name, prev_line, prev_column)));
+                    }
+                    None => {
+                        label_definitions.insert(label_name, (label_start, program.len()));
+                    }
+                }
+                first_token = tokens.next();
+            }
+        }
+
+        // Parse the rest of the line if it's not blank.
+        if let Some((opcode_start, opcode_end, opcode)) = first_token {
+            match Inst::from_str(opcode) {
+                Some(inst) => {
+                    program.push(inst as u8);
+
+                    // Parse the operands.
+                    for _ in 0..inst.num_operands() {
+                        match tokens.next() {
+                            Some((operand_start, operand_end, operand)) => {
+                                if operand.chars().next() == Some('@') {
+                                    let label_name = &operand[1..];
+                                    label_uses.push((label_name, program.len(), operand_start,
+                                                      operand_end));
+
+                                    // Push four zero bytes to be overwritten by the label
+                                    // location later.
+                                    for _ in 0..4 { program.push(0); }
+                                } else if let Ok(number) = operand.parse::<i32>() {
+                                    let operand_index = program.len();
+                                    for _ in 0..4 { program.push(0); }
+                                    write_i32_le(&mut program[operand_index..operand_index + 4],
+                                                 number);
+                                } else {
+                                    errors.push(asm_error(source, operand_start, operand_end,
+                                        format!("Expected label or valid 32-bit signed integer \
+                                                 after '{}', not '{}'", opcode, operand)));
+                                    for _ in 0..4 { program.push(0); }
+                                }
+                            }
+                            None => {
+                                errors.push(asm_error(source, opcode_start, opcode_end,
+                                    format!("Missing one or more operands after '{}'", opcode)));
+                                for _ in 0..4 { program.push(0); }
+                            }
+                        }
+                    }
+                }
+                None => {
+                    errors.push(asm_error(source, opcode_start, opcode_end,
+                        format!("Unrecognized instruction '{}'", opcode)));
+                }
+            }
+        }
+    }
+
+    // Resolve label references and fill in their values in the bytecode.
+    for (label_name, use_index, ref_start, ref_end) in label_uses {
+        match label_definitions.get(label_name) {
+            Some(&(_, target_index)) => {
+                let delta = target_index as i32 - use_index as i32;
+                write_i32_le(&mut program[use_index..use_index + 4], delta);
+            }
+            None => {
+                errors.push(asm_error(source, ref_start, ref_end,
+                    format!("Reference to undefined label '{}'", label_name)));
+            }
+        }
+    }
+
+    if errors.is_empty() { Ok(program) } else { Err(errors) }
+}
+
+/// Renders assembler diagnostics as caret-underlined source snippets, e.g.:
+///
+/// ```text
+/// 2:10: Unrecognized instruction 'prnt'
+///     dup; prnt
+///          ^^^^
+/// ```
+pub fn render_asm_errors(source: &str, errors: &[AsmError]) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut out = String::new();
+    for err in errors {
+        out.push_str(&format!("{}:{}: {}\n", err.line, err.column, err.message));
+        if let Some(line_text) = lines.get(err.line - 1) {
+            out.push_str(line_text);
+            out.push('\n');
+            for _ in 0..err.column - 1 { out.push(' '); }
+            for _ in 0..(err.end - err.start).max(1) { out.push('^'); }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// The inverse of `assemble`: decodes `program` back into assembly text that will assemble
+/// back to identical bytes.
+pub fn disassemble(program: &[u8]) -> Result<String, VmError> {
+    use VmError::*;
+
+    fn is_branch(inst: Inst) -> bool {
+        match inst {
+            Inst::Jz | Inst::Jnz | Inst::Jump | Inst::Call => true,
+            _ => false,
+        }
+    }
+
+    // Decodes the instruction at the cursor's current position, returning the position it
+    // started at along with the decoded opcode and its operand, if any.
+    fn decode(cursor: &mut ByteCursor) -> Result<(u64, Inst, Option<i32>), VmError> {
+        let start = cursor.position();
+        let opcode = try!(cursor.read_u8());
+        let inst = try!(Inst::from_u8(opcode).ok_or(InvalidOpcode));
+        let operand = if inst.num_operands() > 0 {
+            Some(try!(cursor.read_i32()))
+        } else {
+            None
+        };
+        Ok((start, inst, operand))
+    }
+
+    // A branch operand is a delta from the position right after the opcode byte, exactly as
+    // computed by the `jump` helper in `Vm::execute` and by `assemble`'s label resolution.
+    fn branch_target(start: u64, delta: i32) -> u64 {
+        (start as i64 + 1 + delta as i64) as u64
+    }
+
+    // First pass: find every jump/call target so we know where to emit synthetic labels.
+    // Labels can only be emitted at instruction starts and at the end of the program, so a
+    // branch to any other offset keeps its raw delta.
+    let mut labels = BTreeSet::new();
+    let mut starts = BTreeSet::new();
+    {
+        let mut cursor = ByteCursor::new(program);
+        while (cursor.position() as usize) < program.len() {
+            let (start, inst, operand) = try!(decode(&mut cursor));
+            starts.insert(start);
+            if is_branch(inst) {
+                labels.insert(branch_target(start, operand.unwrap()));
+            }
+        }
+        starts.insert(program.len() as u64);
+    }
+
+    // Second pass: emit one line per instruction, with a synthetic `L<offset>:` label
+    // wherever a previous pass found a branch targeting that offset.
+    let mut out = String::new();
+    let mut cursor = ByteCursor::new(program);
+    while (cursor.position() as usize) < program.len() {
+        let start = cursor.position();
+        if labels.contains(&start) {
+            out.push_str(&format!("L{}:\n", start));
+        }
+
+        let (_, inst, operand) = try!(decode(&mut cursor));
+        out.push_str("    ");
+        out.push_str(inst.name());
+        match operand {
+            Some(delta) if is_branch(inst) && starts.contains(&branch_target(start, delta)) => {
+                out.push_str(&format!(" @L{}", branch_target(start, delta)));
+            }
+            Some(operand) => out.push_str(&format!(" {}", operand)),
+            None => {}
+        }
+        out.push('\n');
+    }
+    if labels.contains(&(program.len() as u64)) {
+        out.push_str(&format!("L{}:\n", program.len()));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_i32(program: &mut Vec<u8>, inst: Inst, val: i32) {
+        program.push(inst as u8);
+        let start = program.len();
+        for _ in 0..4 { program.push(0); }
+        write_i32_le(&mut program[start..start + 4], val);
+    }
+
+    #[test]
+    fn trap_resume_skips_the_faulting_instruction() {
+        // int 1; push 42; halt -- Resume continues right after the faulting `int`, not from
+        // the start of it, so exactly one `push` should run.
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Int, 1);
+        push_i32(&mut program, Inst::Push, 42);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+        vm.install_trap(TrapCause::Software(1), |_, _| TrapAction::Resume);
+        vm.execute(&program).unwrap();
+
+        assert_eq!(vm.stack_idx, 1);
+        assert_eq!(vm.stack[0], 42);
+    }
+
+    #[test]
+    fn trap_jump_redirects_execution() {
+        // int 2; push 1 (skipped); push 7 (jump target); halt
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Int, 2);
+        push_i32(&mut program, Inst::Push, 1);
+        let target = program.len() as u64;
+        push_i32(&mut program, Inst::Push, 7);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+        vm.install_trap(TrapCause::Software(2), move |_, _| TrapAction::Jump(target));
+        vm.execute(&program).unwrap();
+
+        assert_eq!(vm.stack_idx, 1);
+        assert_eq!(vm.stack[0], 7);
+    }
+
+    #[test]
+    fn trap_abort_returns_the_given_error() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Int, 3);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+        vm.install_trap(TrapCause::Software(3), |_, _| TrapAction::Abort(VmError::UnhandledTrap(99)));
+
+        assert_eq!(vm.execute(&program), Err(VmError::UnhandledTrap(99)));
+    }
+
+    #[test]
+    fn timer_resume_does_not_drop_the_coincident_instruction() {
+        // Five pushes in a row, with the timer set to fire every other cycle. If `Resume`
+        // skipped the instruction the timer coincided with (the old, buggy behavior), every
+        // other push would be silently dropped; instead each one should still land on the stack.
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, 1);
+        push_i32(&mut program, Inst::Push, 2);
+        push_i32(&mut program, Inst::Push, 3);
+        push_i32(&mut program, Inst::Push, 4);
+        push_i32(&mut program, Inst::Push, 5);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+        vm.set_timer_period(Some(2));
+        vm.install_trap(TrapCause::Timer, |_, _| TrapAction::Resume);
+        vm.execute(&program).unwrap();
+
+        assert_eq!(vm.stack_idx, 5);
+        assert_eq!(&vm.stack[0..5], &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn ret_with_empty_control_stack_underflows() {
+        let program = vec![Inst::Ret as u8];
+        let mut vm = Vm::new(16, 0);
+
+        assert_eq!(vm.execute(&program), Err(VmError::ControlStackUnderflow));
+    }
+
+    #[test]
+    fn checked_add_traps_on_overflow() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, i32::MAX);
+        push_i32(&mut program, Inst::Push, 1);
+        program.push(Inst::Add as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+        vm.set_checked_arithmetic(true);
+
+        assert_eq!(vm.execute(&program), Err(VmError::ArithmeticOverflow));
+    }
+
+    #[test]
+    fn unchecked_add_wraps_on_overflow() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, i32::MAX);
+        push_i32(&mut program, Inst::Push, 1);
+        program.push(Inst::Add as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+        vm.execute(&program).unwrap();
+
+        assert_eq!(vm.stack_idx, 1);
+        assert_eq!(vm.stack[0], i32::MIN);
+    }
+
+    #[test]
+    fn div_by_zero_traps() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, 10);
+        push_i32(&mut program, Inst::Push, 0);
+        program.push(Inst::Div as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+
+        assert_eq!(vm.execute(&program), Err(VmError::DivideByZero));
+    }
+
+    #[test]
+    fn div_min_by_negative_one_traps_on_overflow() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, i32::MIN);
+        push_i32(&mut program, Inst::Push, -1);
+        program.push(Inst::Div as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+
+        assert_eq!(vm.execute(&program), Err(VmError::ArithmeticOverflow));
+    }
+
+    #[test]
+    fn divmod_computes_quotient_and_remainder() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, 17);
+        push_i32(&mut program, Inst::Push, 5);
+        program.push(Inst::Divmod as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 0);
+        vm.execute(&program).unwrap();
+
+        assert_eq!(vm.stack_idx, 2);
+        assert_eq!(vm.stack[0], 3);
+        assert_eq!(vm.stack[1], 2);
+    }
+
+    #[test]
+    fn store_then_load_round_trips_a_value() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, 0);
+        push_i32(&mut program, Inst::Push, 1234);
+        program.push(Inst::Store as u8);
+        push_i32(&mut program, Inst::Push, 0);
+        program.push(Inst::Load as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 1);
+        vm.execute(&program).unwrap();
+
+        assert_eq!(vm.stack_idx, 1);
+        assert_eq!(vm.stack[0], 1234);
+    }
+
+    #[test]
+    fn store8_then_load8_round_trips_a_byte() {
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, 0);
+        push_i32(&mut program, Inst::Push, 0xab);
+        program.push(Inst::Store8 as u8);
+        push_i32(&mut program, Inst::Push, 0);
+        program.push(Inst::Load8 as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 1);
+        vm.execute(&program).unwrap();
+
+        assert_eq!(vm.stack_idx, 1);
+        assert_eq!(vm.stack[0], 0xab);
+    }
+
+    #[test]
+    fn load_past_mapped_memory_faults() {
+        // A single memory page, loading from the first address of the page that follows it.
+        let mut program = Vec::new();
+        push_i32(&mut program, Inst::Push, PAGE_SIZE as i32);
+        program.push(Inst::Load as u8);
+        program.push(Inst::Halt as u8);
+
+        let mut vm = Vm::new(16, 1);
+
+        assert_eq!(vm.execute(&program), Err(VmError::MemoryFault { addr: PAGE_SIZE as u32 }));
+    }
+
+    const FACTORIAL_SOURCE: &str = r"
+        push 10
+        call @fact
+        print
+        halt
+
+fact:   push 1
+        swap
+loop:   dup; jz @done
+        dup; cpush
+        mul
+        cpop; push 1; sub
+        jump @loop
+done:   pop
+        ret
+    ";
+
+    #[test]
+    fn disassemble_round_trips_through_assemble() {
+        let program = assemble(FACTORIAL_SOURCE).unwrap();
+        let text = disassemble(&program).unwrap();
+        let reassembled = assemble(&text).unwrap();
+
+        assert_eq!(program, reassembled);
+    }
+}